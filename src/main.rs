@@ -19,71 +19,501 @@
 
 #[macro_use]
 extern crate clap;
+extern crate blake3;
 extern crate dialoguer;
 extern crate indicatif;
+extern crate rayon;
+extern crate region;
 extern crate rust_sodium;
 extern crate sodium_stream;
+extern crate tiny_keccak;
 
 use dialoguer::PasswordInput;
 use indicatif::{ProgressBar, ProgressStyle};
-use rust_sodium::{crypto::stream::xchacha20, randombytes, utils::memzero};
+use rust_sodium::{
+    crypto::{box_, scalarmult::curve25519, secretbox, stream::xchacha20},
+    randombytes, utils::memzero,
+};
 use sodium_stream::{xfile, util};
-use std::{fs, io::prelude::*, io::SeekFrom};
+use std::{
+    fs, io::prelude::*, io::SeekFrom, ops::{Deref, DerefMut}, os::unix::fs::OpenOptionsExt,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc}, thread,
+};
+use tiny_keccak::{CShake, Xof};
+
+// bridges types whose backing bytes we want to lock/zero; String can't
+// implement AsMut<[u8]> safely since it must stay valid utf-8, but we
+// only ever zero it on drop, and all-zero bytes are still valid utf-8
+trait AsMutBytes {
+    fn as_mut_bytes(&mut self) -> &mut [u8];
+}
+
+impl AsMutBytes for String {
+    fn as_mut_bytes(&mut self) -> &mut [u8] { unsafe { self.as_bytes_mut() } }
+}
+
+impl AsMutBytes for Vec<u8> {
+    fn as_mut_bytes(&mut self) -> &mut [u8] { self.as_mut_slice() }
+}
+
+// wraps a buffer, locking its pages so they can't be paged to swap for as
+// long as it's alive, and zeroing+unlocking them on drop
+struct Locked<T: AsMutBytes> {
+    inner: T,
+    guard: Option<region::LockGuard>,
+}
+
+impl<T: AsMutBytes> Locked<T> {
+    fn new(mut inner: T) -> Locked<T> {
+        let bytes = inner.as_mut_bytes();
+        let guard = if bytes.is_empty() {
+            None
+        } else {
+            Some(region::lock(bytes.as_ptr(), bytes.len()).expect("mlock err"))
+        };
+        Locked { inner, guard }
+    }
+}
+
+impl<T: AsMutBytes> Deref for Locked<T> {
+    type Target = T;
+    fn deref(&self) -> &T { &self.inner }
+}
+
+impl<T: AsMutBytes> DerefMut for Locked<T> {
+    fn deref_mut(&mut self) -> &mut T { &mut self.inner }
+}
+
+impl<T: AsMutBytes> Drop for Locked<T> {
+    fn drop(&mut self) {
+        memzero(self.inner.as_mut_bytes());
+        self.guard.take();
+    }
+}
+
+// the data-encryption key package is laid out as key[..32],
+// nonce[32..56], mac[56..]
+const DEK_LEN: usize        = 128;
+const WRAP_NONCE_LEN: usize = 24;
+const WRAPPED_DEK_LEN: usize = DEK_LEN + secretbox::MACBYTES;
+
+const MAC_HMAC: u8   = 0;
+const MAC_BLAKE3: u8 = 1;
+const BLAKE3_TAG_LEN: usize = 32;
+
+// hmac[64] + kek salt[16]; this is the region xfile itself reserves at
+// the front of the ciphertext file, so it stays exactly the original
+// size no matter how much envelope material we add on top of it
+const HEADER_LEN: usize = 64 + 16;
+
+// dek-wrap nonce[24] + wrapped dek[..] + mac algo[1] + blake3 tag[32]
+// (tag unused/zeroed when MAC_HMAC is selected); this rides alongside
+// the ciphertext file in its own sidecar (see `envelope_path`) instead
+// of being crammed into xfile's fixed-size header region
+const ENVELOPE_LEN: usize = WRAP_NONCE_LEN + WRAPPED_DEK_LEN + 1 + BLAKE3_TAG_LEN;
+const ENV_MAC_ALGO_OFFSET: usize   = WRAP_NONCE_LEN + WRAPPED_DEK_LEN;
+const ENV_BLAKE3_TAG_OFFSET: usize = ENV_MAC_ALGO_OFFSET + 1;
+
+// sidecar path for the DEK envelope; kept separate from the ciphertext
+// file itself so growing the envelope never risks colliding with
+// whatever fixed-size header xfile::encrypt_file/decrypt_file reserve
+fn envelope_path(path: &str) -> String {
+    format!("{}.env", path)
+}
+
+fn set_envelope(path: &str, wrap_nonce: &[u8], wrapped_dek: &[u8], mac_algo: u8, blake3_tag: &[u8]) {
+    let mut out = fs::File::create(envelope_path(path)).expect("io err");
+    out.write(&wrap_nonce[..]).expect("io err");
+    out.write(&wrapped_dek[..]).expect("io err");
+    out.write(&[mac_algo]).expect("io err");
+    out.write(&blake3_tag[..]).expect("io err");
+}
+
+fn get_envelope(path: &str) -> Vec<u8> {
+    let mut f = fs::File::open(envelope_path(path)).expect("io err");
+    let mut b = vec![0; ENVELOPE_LEN];
+    f.read(&mut b).expect("io err");
+    b
+}
+
+// read granularity for blake3_tag; large enough that each update_rayon
+// call gives the thread pool plenty of 1024-byte BLAKE3 chunks to split
+// across workers
+const BLAKE3_READ_LEN: usize = 1 << 20;
+
+// streams the ciphertext through a keyed BLAKE3 hash for an augmenting,
+// faster-on-large-files authentication tag alongside xfile's own hmac;
+// seeks past the HEADER_LEN-byte region xfile reserves so the tag only
+// ever covers ciphertext, matching at both seal and verify time. BLAKE3's
+// tree structure lets each buffer's chunks hash in parallel across
+// `threads` workers before being combined, same as xfile's own encrypt
+// and decrypt passes
+fn blake3_tag(path: &str, key: &[u8], threads: usize) -> [u8; BLAKE3_TAG_LEN] {
+    let mut key_bytes = [0u8; 32];
+    key_bytes.copy_from_slice(&key[..32]);
+    let mut hasher = blake3::Hasher::new_keyed(&key_bytes);
+    let mut f = fs::File::open(path).expect("io err");
+    f.seek(SeekFrom::Start(HEADER_LEN as u64)).expect("io err");
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().expect("thread pool err");
+    let mut buf = vec![0u8; BLAKE3_READ_LEN];
+    loop {
+        let n = f.read(&mut buf).expect("io err");
+        if n == 0 { break; }
+        pool.install(|| { hasher.update_rayon(&buf[..n]); });
+    }
+    *hasher.finalize().as_bytes()
+}
+
+// format version baked into every cSHAKE256 customization string, so a
+// future format change can't silently collide with this one's key material
+const FORMAT_VERSION: &str = "obxr-v1";
+
+// expands `input` into `out_len` independent bytes via cSHAKE256, using a
+// fixed function-name string and a customization string that binds the
+// format version and the caller's purpose (e.g. "enc-key" vs "mac-key"),
+// so the same input never yields reusable key material for two purposes
+fn cshake_expand(input: &[u8], purpose: &str, out_len: usize) -> Vec<u8> {
+    let custom = format!("{}/{}", FORMAT_VERSION, purpose);
+    let mut hasher = CShake::v256(b"obxr", custom.as_bytes());
+    hasher.update(input);
+    let mut out = vec![0u8; out_len];
+    hasher.squeeze(&mut out);
+    out
+}
+
+fn derive_kek(password: &[u8], salt: &[u8], threads: usize, max_argon: usize) -> Locked<Vec<u8>> {
+    let argon_out = util::secrets_from_argon(password, salt, &[], threads, max_argon).expect("argon err");
+    Locked::new(cshake_expand(&argon_out, "kek", 32))
+}
+
+fn wrap_dek(dek: &[u8], kek: &[u8], nonce: &secretbox::Nonce) -> Vec<u8> {
+    let key = secretbox::Key::from_slice(&kek[..32]).expect("key err");
+    secretbox::seal(dek, nonce, &key)
+}
+
+fn unwrap_dek(wrapped: &[u8], kek: &[u8], nonce: &secretbox::Nonce) -> Vec<u8> {
+    let key = secretbox::Key::from_slice(&kek[..32]).expect("key err");
+    secretbox::open(wrapped, nonce, &key).expect("auth err")
+}
 
 fn set_secret(output: &str, hmac: &[u8], salt: &[u8]) {
     let mut out = fs::OpenOptions::new().write(true).open(output).expect("io err");
     out.seek(SeekFrom::Start(0)).expect("io err");
-    out.write(&hmac[..]).expect("io err"); out.write(&salt[..]).expect("io err");
+    out.write(&hmac[..]).expect("io err");
+    out.write(&salt[..]).expect("io err");
+}
+
+// header for recipient-sealed files: 64 bytes of hmac followed by the
+// sender's ephemeral public key, in place of the password salt
+fn set_box_header(output: &str, hmac: &[u8], eph_pk: &[u8]) {
+    let mut out = fs::OpenOptions::new().write(true).open(output).expect("io err");
+    out.seek(SeekFrom::Start(0)).expect("io err");
+    out.write(&hmac[..]).expect("io err"); out.write(&eph_pk[..]).expect("io err");
+}
+
+fn get_box_header(input: &str) -> Vec<u8> {
+    let mut f = fs::File::open(input).expect("io err");
+    let mut b = [0;96];
+    f.read(&mut b).expect("io err");
+    b.to_vec()
 }
 
-fn do_box(input: &str, threads: usize, max_mem: usize, max_argon: usize) {
-    let output       = format!("{}.{}", input.split_at(input.rfind('.').unwrap()).0, "bin");
-    let mut password = PasswordInput::new("Password").confirm("Confirm", "Mismatch").interact().expect("no password");
-    let salt         = randombytes::randombytes(16);
+// derives independent key/nonce/mac material from an X25519 shared secret
+// via cSHAKE256, binding in the ephemeral public key so each seal uses an
+// independent derivation even when the same recipient is sealed to twice
+fn expand_shared_secret(shared: &[u8], eph_pk: &[u8]) -> Vec<u8> {
+    let mut ctx = Vec::with_capacity(shared.len() + eph_pk.len());
+    ctx.extend_from_slice(shared);
+    ctx.extend_from_slice(eph_pk);
+
+    let mut out = cshake_expand(&ctx, "enc-key", 32);
+    out.extend_from_slice(&cshake_expand(&ctx, "nonce", 24));
+    out.extend_from_slice(&cshake_expand(&ctx, "mac-key", 32));
+    out
+}
+
+// cap on a stdin-supplied password; read_line's organic reallocation would
+// otherwise leave unzeroed, unlocked copies of the password on the heap, so
+// we read raw bytes into a buffer that's already locked at its final size
+const STDIN_PASSWORD_MAX: usize = 4096;
+
+// reads the passphrase from an environment variable, from stdin, or
+// falls back to the interactive prompt+confirm, in that order
+fn acquire_password(label: &str, password_env: Option<&str>, password_stdin: bool) -> Locked<Vec<u8>> {
+    if let Some(var) = password_env {
+        let value = std::env::var(var).expect("env var not set");
+        std::env::remove_var(var);
+        return Locked::new(value.into_bytes());
+    }
+    if password_stdin {
+        let mut buf = Locked::new(vec![0u8; STDIN_PASSWORD_MAX]);
+        let n = std::io::stdin().read(&mut buf[..]).expect("io err");
+        let mut len = n;
+        if len > 0 && buf[len - 1] == b'\n' {
+            len -= 1;
+            if len > 0 && buf[len - 1] == b'\r' { len -= 1; }
+        }
+        buf.truncate(len);
+        return buf;
+    }
+    Locked::new(PasswordInput::new(label).confirm("Confirm", "Mismatch").interact().expect("no password").into_bytes())
+}
+
+fn do_box(input: &str, mac: &str, password_env: Option<&str>, password_stdin: bool, threads: usize, max_mem: usize, max_argon: usize) {
+    let output   = format!("{}.{}", input.split_at(input.rfind('.').unwrap()).0, "bin");
+    let password = acquire_password("Password", password_env, password_stdin);
+    let salt     = randombytes::randombytes(16);
 
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(ProgressStyle::default_spinner().template("{msg} {spinner}").tick_chars("☆ﾟ.*･｡ﾟ★"));
     spinner.enable_steady_tick(50);
 
     spinner.set_message("Checking password... ");
-    let secret = util::secrets_from_argon(password.as_bytes(), &salt, &[], threads, max_argon).expect("argon err");
-    memzero(unsafe { password.as_bytes_mut() } );
+    let kek = derive_kek(&password, &salt, threads, max_argon);
+    drop(password);
+
+    spinner.set_message("Generating key... ");
+    let dek         = Locked::new(randombytes::randombytes(DEK_LEN));
+    let wrap_nonce  = secretbox::gen_nonce();
+    let wrapped_dek = wrap_dek(&dek, &kek, &wrap_nonce);
+    drop(kek);
     spinner.set_message("Encrypting...");
 
-    let key = xchacha20::Key::from_slice(&secret[..32]).expect("key err");
-    let non = xchacha20::Nonce::from_slice(&secret[32..56]).expect("nonce err");
-    let mac = &secret[56..];
+    let key     = xchacha20::Key::from_slice(&dek[..32]).expect("key err");
+    let non     = xchacha20::Nonce::from_slice(&dek[32..56]).expect("nonce err");
+    let mac_key = &dek[56..];
 
-    let hmac = xfile::encrypt_file(input, &output, &key, &non, threads, max_mem, mac);
-    spinner.set_message("Tagging... ");
+    let hmac = xfile::encrypt_file(input, &output, &key, &non, threads, max_mem, mac_key);
     set_secret(&output, &hmac[..], &salt[..]);
+    spinner.set_message("Tagging... ");
+
+    let (mac_algo, tag) = if mac == "blake3" {
+        (MAC_BLAKE3, blake3_tag(&output, mac_key, threads))
+    } else {
+        (MAC_HMAC, [0u8; BLAKE3_TAG_LEN])
+    };
+    set_envelope(&output, &wrap_nonce.0[..], &wrapped_dek[..], mac_algo, &tag[..]);
 }
 
 fn get_secret(input: &str) -> Vec<u8> {
     let mut f = fs::File::open(input).expect("io err");
-    let mut b = [0;80];
+    let mut b = vec![0; HEADER_LEN];
     f.read(&mut b).expect("io err");
-    b.to_vec()
+    b
+}
+
+fn do_unbox(input: &str, password_env: Option<&str>, password_stdin: bool, threads: usize, max_mem: usize, max_argon: usize) {
+    let data     = get_secret(input);
+    let env      = get_envelope(input);
+    let password = acquire_password("Password", password_env, password_stdin);
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::default_spinner().template("{msg} {spinner}").tick_chars("☆ﾟ.*･｡ﾟ★"));
+    spinner.enable_steady_tick(50);
+
+    spinner.set_message("Checking password... ");
+    let kek = derive_kek(&password, &data[64..80], threads, max_argon);
+    drop(password);
+
+    spinner.set_message("Unwrapping key... ");
+    let wrap_nonce = secretbox::Nonce::from_slice(&env[..WRAP_NONCE_LEN]).expect("nonce err");
+    let dek        = Locked::new(unwrap_dek(&env[WRAP_NONCE_LEN..ENV_MAC_ALGO_OFFSET], &kek, &wrap_nonce));
+    drop(kek);
+
+    if env[ENV_MAC_ALGO_OFFSET] == MAC_BLAKE3 {
+        spinner.set_message("Verifying BLAKE3 tag... ");
+        let expected = &env[ENV_BLAKE3_TAG_OFFSET..ENVELOPE_LEN];
+        let computed = blake3_tag(input, &dek[56..], threads);
+        if !rust_sodium::utils::memcmp(&computed[..], expected) {
+            panic!("auth err");
+        }
+    }
+
+    spinner.set_message("Authenticating and decrypting... ");
+    let output = format!("{}.{}", input.split_at(input.rfind('.').unwrap()).0, "out");
+
+    let key = xchacha20::Key::from_slice(&dek[..32]).expect("key err");
+    let non = xchacha20::Nonce::from_slice(&dek[32..56]).expect("nonce err");
+
+    xfile::decrypt_file(input, &output, &key, &non, threads, max_mem, &data[..64], &dek[56..]);
 }
 
-fn do_unbox(input: &str, threads: usize, max_mem: usize, max_argon: usize) {
+fn do_rekey(input: &str, old_env: Option<&str>, old_stdin: bool, new_env: Option<&str>, new_stdin: bool, threads: usize, max_argon: usize) {
     let data         = get_secret(input);
-    let mut password = PasswordInput::new("Password").confirm("Confirm", "Mismatch").interact().expect("no password");
+    let env          = get_envelope(input);
+    let old_password = acquire_password("Current password", old_env, old_stdin);
 
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(ProgressStyle::default_spinner().template("{msg} {spinner}").tick_chars("☆ﾟ.*･｡ﾟ★"));
     spinner.enable_steady_tick(50);
 
     spinner.set_message("Checking password... ");
-    let secret = util::secrets_from_argon(password.as_bytes(), &data[64..], &[], threads, max_argon).expect("argon err");
-    memzero(unsafe { password.as_bytes_mut() } );
+    let old_kek = derive_kek(&old_password, &data[64..80], threads, max_argon);
+    drop(old_password);
+
+    spinner.set_message("Unwrapping key... ");
+    let old_nonce = secretbox::Nonce::from_slice(&env[..WRAP_NONCE_LEN]).expect("nonce err");
+    let dek       = Locked::new(unwrap_dek(&env[WRAP_NONCE_LEN..ENV_MAC_ALGO_OFFSET], &old_kek, &old_nonce));
+    drop(old_kek);
+
+    let new_password = acquire_password("New password", new_env, new_stdin);
+    let new_salt     = randombytes::randombytes(16);
+    spinner.set_message("Deriving new key... ");
+    let new_kek = derive_kek(&new_password, &new_salt, threads, max_argon);
+    drop(new_password);
+
+    spinner.set_message("Wrapping key... ");
+    let new_nonce   = secretbox::gen_nonce();
+    let wrapped_dek = wrap_dek(&dek, &new_kek, &new_nonce);
+    drop(new_kek);
+    drop(dek);
+
+    spinner.set_message("Rewriting header... ");
+    set_secret(input, &data[..64], &new_salt[..]);
+    set_envelope(input, &new_nonce.0[..], &wrapped_dek[..], env[ENV_MAC_ALGO_OFFSET], &env[ENV_BLAKE3_TAG_OFFSET..ENVELOPE_LEN]);
+}
+
+// creates `path` with mode 0600 from the moment it's opened, so a raw
+// secret key is never briefly readable under the process umask the way
+// fs::write + a follow-up chmod would leave it
+fn write_secret_file(path: &str, bytes: &[u8]) {
+    let mut f = fs::OpenOptions::new().write(true).create(true).mode(0o600).open(path).expect("io err");
+    f.write(bytes).expect("io err");
+}
+
+fn do_keygen(output: &str) {
+    let (pk, sk) = box_::gen_keypair();
+    fs::write(format!("{}.pub", output), &pk.0[..]).expect("io err");
+    write_secret_file(&format!("{}.sec", output), &sk.0[..]);
+    println!("wrote {}.pub and {}.sec", output, output);
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// fixed, public domain-separation salt: a brain wallet has no salt file of
+// its own, so the passphrase (plus the counter mixed in below) is the only
+// secret input, and the keypair must be reproducible from memory alone
+const BRAIN_SALT: &[u8; 16] = b"obxr-brain-v1\0\0\0";
+
+// derives an X25519 keypair straight from a passphrase and a counter (used
+// only by --prefix search to try many candidates), so no secret key file
+// needs to exist anywhere for the wallet to be regenerated later
+fn derive_brain_keypair(passphrase: &[u8], counter: u64, max_argon: usize) -> (box_::PublicKey, box_::SecretKey) {
+    let mut buf = Vec::with_capacity(passphrase.len() + 8);
+    buf.extend_from_slice(passphrase);
+    buf.extend_from_slice(&counter.to_le_bytes());
+    let input = Locked::new(buf);
+    let argon_out = Locked::new(util::secrets_from_argon(&input, BRAIN_SALT, &[], 1, max_argon).expect("argon err"));
+    let sk_bytes  = Locked::new(cshake_expand(&argon_out, "brain-sk", 32));
+
+    let sk       = box_::SecretKey::from_slice(&sk_bytes).expect("key err");
+    let pk_point = curve25519::scalarmult_base(&curve25519::Scalar::from_slice(&sk_bytes).expect("key err"));
+    let pk       = box_::PublicKey::from_slice(&pk_point.0).expect("key err");
+    (pk, sk)
+}
+
+fn do_brain(passphrase: &[u8], prefix: Option<&str>, threads: usize, max_argon: usize, output: &str, write_secret: bool) {
+    let passphrase = Arc::new(Locked::new(passphrase.to_vec()));
+
+    let prefix = match prefix {
+        None => {
+            let (pk, sk) = derive_brain_keypair(&passphrase, 0, max_argon);
+            fs::write(format!("{}.pub", output), &pk.0[..]).expect("io err");
+            if write_secret {
+                write_secret_file(&format!("{}.sec", output), &sk.0[..]);
+            }
+            println!("derived deterministic keypair (counter 0); public key written to {}.pub{}", output, if write_secret { format!(" (secret key written to {}.sec)", output) } else { String::new() });
+            return;
+        }
+        Some(prefix) => prefix.to_lowercase(),
+    };
+    assert!(prefix.len() <= 64 && prefix.chars().all(|c| c.is_digit(16)), "prefix must be a hex string of at most 64 characters");
+
+    let found    = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..threads).map(|worker| {
+        let passphrase = Arc::clone(&passphrase);
+        let prefix     = prefix.clone();
+        let found      = Arc::clone(&found);
+        let tx         = tx.clone();
+        thread::spawn(move || {
+            let mut counter = worker as u64;
+            while !found.load(Ordering::Relaxed) {
+                let (pk, sk) = derive_brain_keypair(&passphrase, counter, max_argon);
+                if hex_encode(&pk.0).starts_with(&prefix) {
+                    found.store(true, Ordering::Relaxed);
+                    let _ = tx.send((counter, pk, sk));
+                    break;
+                }
+                counter += threads as u64;
+            }
+        })
+    }).collect();
+    drop(tx);
+
+    if let Ok((counter, pk, sk)) = rx.recv() {
+        fs::write(format!("{}.pub", output), &pk.0[..]).expect("io err");
+        if write_secret {
+            write_secret_file(&format!("{}.sec", output), &sk.0[..]);
+        }
+        println!("found matching keypair at counter {}; public key written to {}.pub{}", counter, output, if write_secret { format!(" (secret key written to {}.sec)", output) } else { String::new() });
+    }
+    for w in workers { let _ = w.join(); }
+}
+
+fn do_seal(input: &str, recipient: &str, threads: usize, max_mem: usize) {
+    let output        = format!("{}.{}", input.split_at(input.rfind('.').unwrap()).0, "bin");
+    let recipient_raw = fs::read(recipient).expect("io err");
+    let recipient_pk  = box_::PublicKey::from_slice(&recipient_raw).expect("key err");
+    let (eph_pk, eph_sk) = box_::gen_keypair();
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::default_spinner().template("{msg} {spinner}").tick_chars("☆ﾟ.*･｡ﾟ★"));
+    spinner.enable_steady_tick(50);
+
+    spinner.set_message("Deriving shared secret... ");
+    let shared = curve25519::scalarmult(
+        &curve25519::Scalar::from_slice(&eph_sk.0).expect("key err"),
+        &curve25519::GroupElement::from_slice(&recipient_pk.0).expect("key err"),
+    ).expect("ecdh err");
+    let secret = Locked::new(expand_shared_secret(&shared.0, &eph_pk.0));
+    spinner.set_message("Encrypting...");
+
+    let key = xchacha20::Key::from_slice(&secret[..32]).expect("key err");
+    let non = xchacha20::Nonce::from_slice(&secret[32..56]).expect("nonce err");
+    let mac = &secret[56..];
+
+    let hmac = xfile::encrypt_file(input, &output, &key, &non, threads, max_mem, mac);
+    spinner.set_message("Tagging... ");
+    set_box_header(&output, &hmac[..], &eph_pk.0[..]);
+}
+
+fn do_open(input: &str, secret: &str, threads: usize, max_mem: usize) {
+    let header  = get_box_header(input);
+    let sk_raw  = fs::read(secret).expect("io err");
+    let my_sk   = box_::SecretKey::from_slice(&sk_raw).expect("key err");
+    let eph_pk  = box_::PublicKey::from_slice(&header[64..96]).expect("key err");
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::default_spinner().template("{msg} {spinner}").tick_chars("☆ﾟ.*･｡ﾟ★"));
+    spinner.enable_steady_tick(50);
+
+    spinner.set_message("Deriving shared secret... ");
+    let shared = curve25519::scalarmult(
+        &curve25519::Scalar::from_slice(&my_sk.0).expect("key err"),
+        &curve25519::GroupElement::from_slice(&eph_pk.0).expect("key err"),
+    ).expect("ecdh err");
+    let secret = Locked::new(expand_shared_secret(&shared.0, &eph_pk.0));
     spinner.set_message("Authenticating and decrypting... ");
     let output = format!("{}.{}", input.split_at(input.rfind('.').unwrap()).0, "out");
 
     let key = xchacha20::Key::from_slice(&secret[..32]).expect("key err");
     let non = xchacha20::Nonce::from_slice(&secret[32..56]).expect("nonce err");
 
-    xfile::decrypt_file(input, &output, &key, &non, threads, max_mem, &data[..64], &secret[56..]);
+    xfile::decrypt_file(input, &output, &key, &non, threads, max_mem, &header[..64], &secret[56..]);
 }
 
 fn main() {
@@ -97,10 +527,45 @@ fn main() {
                             (@subcommand box =>
                                 (about: "encrypts and tags")
                                 (@arg INPUT: +required "no input!")
+                                (@arg MAC: --mac +takes_value "authentication tag algorithm: hmac (default) or blake3")
+                                (@arg PASSWORD_ENV: --("password-env") +takes_value "reads the password from this environment variable instead of prompting")
+                                (@arg PASSWORD_STDIN: --("password-stdin") "reads the password from stdin instead of prompting")
                             )
                             (@subcommand unbox =>
                                 (about: "authenticates and decrypts")
                                 (@arg INPUT: +required "no input!")
+                                (@arg PASSWORD_ENV: --("password-env") +takes_value "reads the password from this environment variable instead of prompting")
+                                (@arg PASSWORD_STDIN: --("password-stdin") "reads the password from stdin instead of prompting")
+                            )
+                            (@subcommand rekey =>
+                                (about: "re-wraps the data-encryption key under a new password without touching ciphertext")
+                                (@arg INPUT: +required "no input!")
+                                (@arg OLD_PASSWORD_ENV: --("old-password-env") +takes_value "reads the current password from this environment variable instead of prompting")
+                                (@arg OLD_PASSWORD_STDIN: --("old-password-stdin") "reads the current password from stdin instead of prompting")
+                                (@arg NEW_PASSWORD_ENV: --("new-password-env") +takes_value "reads the new password from this environment variable instead of prompting")
+                                (@arg NEW_PASSWORD_STDIN: --("new-password-stdin") "reads the new password from stdin instead of prompting")
+                            )
+                            (@subcommand keygen =>
+                                (about: "generates an x25519 keypair (writes OUTPUT.pub and OUTPUT.sec)")
+                                (@arg OUTPUT: +required "no output!")
+                            )
+                            (@subcommand seal =>
+                                (about: "encrypts and tags for a recipient's public key")
+                                (@arg INPUT: +required "no input!")
+                                (@arg RECIPIENT: +required "recipient's public key file")
+                            )
+                            (@subcommand open =>
+                                (about: "authenticates and decrypts with your secret key")
+                                (@arg INPUT: +required "no input!")
+                                (@arg SECRET: +required "your secret key file")
+                            )
+                            (@subcommand brain =>
+                                (about: "derives a deterministic \"brain wallet\" keypair from a passphrase (writes OUTPUT.pub)")
+                                (@arg OUTPUT: +required "no output!")
+                                (@arg PREFIX: --prefix +takes_value "hex prefix to vanity-search for in the derived public key")
+                                (@arg WRITE_SECRET: --("write-secret") "also writes the derived secret key to OUTPUT.sec (0600) so it can be used with `open`")
+                                (@arg PASSPHRASE_ENV: --("passphrase-env") +takes_value "reads the passphrase from this environment variable instead of prompting")
+                                (@arg PASSPHRASE_STDIN: --("passphrase-stdin") "reads the passphrase from stdin instead of prompting")
                             )
                         ).get_matches();
 
@@ -113,9 +578,20 @@ fn main() {
     rust_sodium::init().expect("sodium error");
 
     if let Some(sub) = config.subcommand_matches("box") {
-        do_box(sub.value_of("INPUT").unwrap(), max_threads.parse::<usize>().unwrap(), max_mem.parse::<usize>().unwrap()*1024, max_argon.parse::<usize>().unwrap());
+        do_box(sub.value_of("INPUT").unwrap(), sub.value_of("MAC").unwrap_or("hmac"), sub.value_of("PASSWORD_ENV"), sub.is_present("PASSWORD_STDIN"), max_threads.parse::<usize>().unwrap(), max_mem.parse::<usize>().unwrap()*1024, max_argon.parse::<usize>().unwrap());
     } else if let Some(sub) = config.subcommand_matches("unbox") {
-        do_unbox(sub.value_of("INPUT").unwrap(), max_threads.parse::<usize>().unwrap(), max_mem.parse::<usize>().unwrap()*1024, max_argon.parse::<usize>().unwrap());
+        do_unbox(sub.value_of("INPUT").unwrap(), sub.value_of("PASSWORD_ENV"), sub.is_present("PASSWORD_STDIN"), max_threads.parse::<usize>().unwrap(), max_mem.parse::<usize>().unwrap()*1024, max_argon.parse::<usize>().unwrap());
+    } else if let Some(sub) = config.subcommand_matches("rekey") {
+        do_rekey(sub.value_of("INPUT").unwrap(), sub.value_of("OLD_PASSWORD_ENV"), sub.is_present("OLD_PASSWORD_STDIN"), sub.value_of("NEW_PASSWORD_ENV"), sub.is_present("NEW_PASSWORD_STDIN"), max_threads.parse::<usize>().unwrap(), max_argon.parse::<usize>().unwrap());
+    } else if let Some(sub) = config.subcommand_matches("keygen") {
+        do_keygen(sub.value_of("OUTPUT").unwrap());
+    } else if let Some(sub) = config.subcommand_matches("seal") {
+        do_seal(sub.value_of("INPUT").unwrap(), sub.value_of("RECIPIENT").unwrap(), max_threads.parse::<usize>().unwrap(), max_mem.parse::<usize>().unwrap()*1024);
+    } else if let Some(sub) = config.subcommand_matches("open") {
+        do_open(sub.value_of("INPUT").unwrap(), sub.value_of("SECRET").unwrap(), max_threads.parse::<usize>().unwrap(), max_mem.parse::<usize>().unwrap()*1024);
+    } else if let Some(sub) = config.subcommand_matches("brain") {
+        let passphrase = acquire_password("Passphrase", sub.value_of("PASSPHRASE_ENV"), sub.is_present("PASSPHRASE_STDIN"));
+        do_brain(&passphrase, sub.value_of("PREFIX"), max_threads.parse::<usize>().unwrap(), max_argon.parse::<usize>().unwrap(), sub.value_of("OUTPUT").unwrap(), sub.is_present("WRITE_SECRET"));
     } else {
         println!("no command!");
     }